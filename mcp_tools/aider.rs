@@ -1,7 +1,14 @@
 use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use ignore::WalkBuilder;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
+use tokio::sync::{mpsc, Semaphore};
 use tracing::{debug, error, info};
 use schemars::JsonSchema;
 
@@ -21,7 +28,7 @@ pub struct AiderParams {
     pub options: String, // Changed back from Option<String>
 
     #[serde(default)]
-    #[schemars(description = "Optional: The provider to use (e.g., 'anthropic', 'openai', 'gemini'). Leave empty to auto-detect based on available API keys.")]
+    #[schemars(description = "Optional: The provider to use (e.g., 'anthropic', 'openai', 'gemini', 'ollama', 'openai-compatible'). Leave empty to auto-detect based on available API keys.")]
     pub provider: String, // Changed from Option<String>
 
     #[serde(default)]
@@ -31,6 +38,47 @@ pub struct AiderParams {
     #[serde(default)]
     #[schemars(description = "Optional: Reasoning effort level for OpenAI models. Values: 'low', 'medium', 'high'. Defaults to 'high' if empty.")]
     pub reasoning_effort: String, // Changed from Option<String>
+
+    #[serde(default)]
+    #[schemars(description = "Optional: Base URL for a local or self-hosted endpoint (Ollama, vLLM, LM Studio, LiteLLM, etc). Used by the 'ollama' and 'openai-compatible' providers. Defaults to http://localhost:11434 for Ollama if left empty.")]
+    pub api_base: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: If true, append a JSON-lines record of this run to the history store (see `history_dir`). Defaults to false.")]
+    pub record_history: bool,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: Directory the run history is stored under, relative to `directory` unless absolute. Defaults to '.aider-mcp/history'. Only used when `record_history` is true.")]
+    pub history_dir: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: When non-empty, automatically selects the most relevant files under `directory` for this query (by term-overlap scoring over file contents) and adds them to aider's file list, rather than relying solely on `message`. Respects .gitignore and is capped to a handful of files and total size. Not supported when `remote` is set, since selection walks the local `directory`, not the remote host.")]
+    pub context_query: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: Run aider on a remote host over SSH instead of locally. When present, `directory` is not checked locally; `remote.remote_directory` is used as the working tree on the remote host instead.")]
+    pub remote: Option<RemoteTarget>,
+}
+
+/// A remote host to run aider on over SSH, in place of running it locally.
+/// The same command args `build_command_args` produces for the local path are
+/// reused here, wrapped in an `ssh` invocation, so provider/model/reasoning_effort
+/// handling stays identical between the local and remote paths.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RemoteTarget {
+    #[schemars(description = "Hostname or IP address of the remote machine to run aider on")]
+    pub host: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: SSH user to connect as. Leave empty to use ssh's own default (current user / ssh config).")]
+    pub user: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: SSH port. Leave as 0 to use ssh's own default (22).")]
+    pub port: u16,
+
+    #[schemars(description = "The working tree directory on the remote host to run aider in")]
+    pub remote_directory: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -53,6 +101,158 @@ pub struct AiderResult {
     pub model: Option<String>,
 }
 
+/// An incremental update emitted while an aider run is in progress, plus a
+/// final `Done` event once the process exits.
+#[derive(Debug)]
+pub enum AiderEvent {
+    /// A line of stdout, as it's produced.
+    Stdout(String),
+    /// A line of stderr, as it's produced.
+    Stderr(String),
+    /// The run has finished; carries the same result `execute` would return.
+    Done(AiderResult),
+}
+
+/// A JSON-lines record of a single completed aider run, as written under the
+/// history store directory (see `AiderParams.history_dir`) and read back by
+/// `load_history`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiderRunRecord {
+    /// Unix timestamp (seconds) the run finished.
+    pub timestamp: u64,
+    pub directory: String,
+    pub provider: String,
+    pub model: Option<String>,
+    pub message: String,
+    pub status: i32,
+    pub stdout_len: usize,
+    pub stderr_len: usize,
+    pub full_args: Vec<String>,
+}
+
+/// Records a single option that `build_command_args` chose not to pass
+/// through to aider, and why, so a caller can warn the user instead of the
+/// value silently disappearing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DroppedOption {
+    /// The `AiderParams` option/flag name (e.g. "reasoning_effort").
+    pub option: String,
+    /// The value the user supplied for it.
+    pub value: String,
+    /// Human-readable explanation of why it was dropped.
+    pub reason: String,
+}
+
+const DEFAULT_HISTORY_DIR: &str = ".aider-mcp/history";
+const HISTORY_FILE_NAME: &str = "runs.jsonl";
+
+/// All provider names `build_command_args`/`detect_provider` accept.
+const KNOWN_PROVIDERS: &[&str] = &["anthropic", "openai", "gemini", "ollama", "openai-compatible"];
+
+/// How a provider expects its endpoint override (`AiderParams.api_base`) to be
+/// communicated to aider, if at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ApiBaseMode {
+    /// The provider has no notion of a custom endpoint; a supplied value is dropped.
+    Unsupported,
+    /// Passed as the `--openai-api-base` CLI flag (openai-compatible).
+    CliFlag,
+    /// Exported as the `OLLAMA_API_BASE` env var on the child process (ollama).
+    EnvVar,
+}
+
+/// Declarative description of what a provider supports, consulted by
+/// `build_command_args` instead of branching on the provider string inline.
+/// Adding a provider is a new table entry plus a parametrized test, not a new
+/// match arm in every place provider-specific behavior lives.
+struct ProviderCapabilities {
+    /// Default model to use when neither `AiderParams.model` nor `AIDER_MODEL` is set.
+    default_model: Option<&'static str>,
+    /// Whether this provider needs a cloud `--api-key` (local/self-hosted providers don't).
+    requires_api_key: bool,
+    /// Allowed `reasoning_effort` values, or `None` if the provider doesn't support the flag.
+    reasoning_effort_values: Option<&'static [&'static str]>,
+    /// How `api_base` reaches aider for this provider.
+    api_base_mode: ApiBaseMode,
+}
+
+/// Resolves the history log path for `history_dir` (or `DEFAULT_HISTORY_DIR`
+/// if empty), matching `AiderParams.history_dir`'s documented contract: an
+/// absolute `history_dir` is used as-is, a relative one is resolved against
+/// `directory` (the target working tree), not the MCP server's own cwd.
+fn resolve_history_path(directory: &str, history_dir: &str) -> PathBuf {
+    let dir = if history_dir.trim().is_empty() {
+        DEFAULT_HISTORY_DIR
+    } else {
+        history_dir.trim()
+    };
+    let dir_path = PathBuf::from(dir);
+    let base = if dir_path.is_absolute() {
+        dir_path
+    } else {
+        PathBuf::from(directory).join(dir_path)
+    };
+    base.join(HISTORY_FILE_NAME)
+}
+
+/// Appends `record` as one JSON-lines entry under `history_dir` (resolved
+/// relative to `directory` per `resolve_history_path`). Never fails the
+/// caller's run: any I/O or serialization error is logged and swallowed.
+async fn record_run(directory: &str, history_dir: &str, record: &AiderRunRecord) {
+    let path = resolve_history_path(directory, history_dir);
+
+    if let Some(parent) = path.parent() {
+        if let Err(e) = tokio::fs::create_dir_all(parent).await {
+            error!("Failed to create aider history directory '{}': {}", parent.display(), e);
+            return;
+        }
+    }
+
+    let line = match serde_json::to_string(record) {
+        Ok(line) => line,
+        Err(e) => {
+            error!("Failed to serialize aider run record: {}", e);
+            return;
+        }
+    };
+
+    use tokio::io::AsyncWriteExt;
+    match tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(format!("{}\n", line).as_bytes()).await {
+                error!("Failed to append to aider history file '{}': {}", path.display(), e);
+            }
+        }
+        Err(e) => {
+            error!("Failed to open aider history file '{}': {}", path.display(), e);
+        }
+    }
+}
+
+/// Loads every run record previously appended under `history_dir` (resolved
+/// relative to `directory` per `resolve_history_path`). Malformed lines are
+/// logged and skipped rather than failing the whole load.
+pub async fn load_history(directory: &str, history_dir: &str) -> Result<Vec<AiderRunRecord>> {
+    let path = resolve_history_path(directory, history_dir);
+
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| anyhow!("Failed to read aider history file '{}': {}", path.display(), e))?;
+
+    let mut records = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<AiderRunRecord>(line) {
+            Ok(record) => records.push(record),
+            Err(e) => error!("Skipping malformed aider history record at line {}: {}", i + 1, e),
+        }
+    }
+
+    Ok(records)
+}
+
 pub struct AiderExecutor;
 
 impl AiderExecutor {
@@ -60,13 +260,18 @@ impl AiderExecutor {
         AiderExecutor
     }
 
-    /// Helper method to build command arguments for testing
-    pub fn build_command_args(&self, params: &AiderParams) -> Vec<String> {
+    /// Builds the aider command-line arguments for `params`, plus a record of
+    /// any options that were silently dropped because they don't apply to the
+    /// resolved provider (e.g. `reasoning_effort` outside OpenAI), so callers
+    /// can surface them as warnings instead of the value just vanishing.
+    pub fn build_command_args(&self, params: &AiderParams) -> (Vec<String>, Vec<DroppedOption>) {
+        let mut dropped: Vec<DroppedOption> = Vec::new();
+
         // Determine provider: use explicit parameter if not empty, otherwise detect
         let provider = if !params.provider.trim().is_empty() {
             let p_l = params.provider.trim().to_lowercase();
             // Validate provider name
-            if !["anthropic", "openai", "gemini"].contains(&p_l.as_str()) {
+            if !KNOWN_PROVIDERS.contains(&p_l.as_str()) {
                 error!("Unsupported provider '{}' specified. Defaulting to 'anthropic'.", params.provider);
                 "anthropic".to_string() // Default to anthropic on invalid input
             } else {
@@ -77,6 +282,8 @@ impl AiderExecutor {
             Self::detect_provider() // Auto-detect if empty
         };
 
+        let caps = Self::provider_capabilities(&provider);
+
         // Retrieve API key: provider-specific or AIDER_API_KEY
         let provider_env_key = format!("{}_API_KEY", provider.to_uppercase());
         let api_key = std::env::var(&provider_env_key)
@@ -86,32 +293,26 @@ impl AiderExecutor {
             })
             .unwrap_or_default(); // Use empty string if no key is found
 
-        // Warn if no API key is found
-        if api_key.is_empty() {
+        // Warn if no API key is found for providers that need one
+        if api_key.is_empty() && caps.requires_api_key {
             error!("No API key found for provider '{}'. Checked {} and AIDER_API_KEY", provider, provider_env_key);
         }
 
-        // Get model: use param if not empty, else env var, else provider default
+        // Resolve the base URL for local/self-hosted endpoints, if applicable
+        let api_base = Self::resolve_api_base(&provider, &params.api_base);
+
+        // Get model: use param if not empty, else env var, else provider default from the capability table
         let model = if !params.model.trim().is_empty() {
             Some(params.model.trim().to_string())
         } else {
             std::env::var("AIDER_MODEL").ok().or_else(|| {
-                // Set default models based on provider if env var is also empty
-                match provider.as_str() { // provider is already lowercase String
-                    "anthropic" => {
-                        debug!("Using default Anthropic model: anthropic/claude-3-7-sonnet-20250219");
-                        Some("anthropic/claude-3-7-sonnet-20250219".to_string())
-                    },
-                    "openai" => {
-                        debug!("Using default OpenAI model: openai/o3-mini");
-                        Some("openai/o3-mini".to_string())
-                    },
-                    "gemini" => {
-                        debug!("Using default Gemini model: gemini/gemini-2.5-pro-preview-03-25");
-                        Some("gemini/gemini-2.5-pro-preview-03-25".to_string())
+                match caps.default_model {
+                    Some(default) => {
+                        debug!("Using default model for '{}': {}", provider, default);
+                        Some(default.to_string())
                     }
-                    _ => {
-                        error!("Cannot determine default model for unknown provider: {}", provider);
+                    None => {
+                        debug!("No default model for provider '{}'", provider);
                         None
                     }
                 }
@@ -126,9 +327,44 @@ impl AiderExecutor {
             "--no-detect-urls".to_string(),
         ];
 
-        // Always include the API key flag, even if key is empty
-        cmd_args.push("--api-key".to_string());
-        cmd_args.push(format!("{}={}", provider, api_key));
+        // Local/self-hosted providers skip the cloud --api-key flag; instead the
+        // endpoint is communicated via a provider-specific flag or env var. A key
+        // is still passed along if one was supplied (e.g. an authenticated proxy).
+        if caps.requires_api_key || !api_key.is_empty() {
+            cmd_args.push("--api-key".to_string());
+            cmd_args.push(format!("{}={}", provider, api_key));
+        }
+
+        // api_base reaches aider differently depending on the provider: as a CLI
+        // flag (openai-compatible), as an env var set on the child process in
+        // `execute_streaming` (ollama), or not at all (cloud providers).
+        match caps.api_base_mode {
+            ApiBaseMode::CliFlag => {
+                if let Some(base) = &api_base {
+                    cmd_args.push("--openai-api-base".to_string());
+                    cmd_args.push(base.clone());
+                } else {
+                    debug!("No api_base provided for '{}' provider; relying on aider's own configuration", provider);
+                }
+            }
+            ApiBaseMode::EnvVar => {
+                // Nothing to add to cmd_args; execute_streaming sets the env var.
+            }
+            ApiBaseMode::Unsupported => {
+                if !params.api_base.trim().is_empty() {
+                    let reason = format!(
+                        "api_base is only used by the 'ollama' and 'openai-compatible' providers (provider is '{}')",
+                        provider
+                    );
+                    error!("Dropping api_base: {}", reason);
+                    dropped.push(DroppedOption {
+                        option: "api_base".to_string(),
+                        value: params.api_base.clone(),
+                        reason,
+                    });
+                }
+            }
+        }
 
         // Add model if available
         if let Some(m) = &model {
@@ -139,25 +375,39 @@ impl AiderExecutor {
             info!("Using provider '{}' with no specific model", provider);
         }
 
-        // Add reasoning effort for OpenAI models
-        if provider == "openai" { // provider is already lowercase String
-            let effort = if params.reasoning_effort.trim().is_empty() {
-                "high" // Default if param is empty
-            } else {
-                params.reasoning_effort.trim()
-            };
-            // Validate reasoning_effort - only allow "low", "medium", "high"
-            let valid_efforts = ["low", "medium", "high"];
-            let validated_effort = if valid_efforts.contains(&effort.to_lowercase().as_str()) {
-                effort.to_lowercase() // Use validated lowercase effort
-            } else {
-                error!("Invalid reasoning_effort '{}' specified. Defaulting to 'high'", effort);
-                "high".to_string() // Default to high if invalid
-            };
+        // Add reasoning effort, if the provider supports it
+        match caps.reasoning_effort_values {
+            Some(valid_efforts) => {
+                let effort = if params.reasoning_effort.trim().is_empty() {
+                    "high" // Default if param is empty
+                } else {
+                    params.reasoning_effort.trim()
+                };
+                let validated_effort = if valid_efforts.contains(&effort.to_lowercase().as_str()) {
+                    effort.to_lowercase() // Use validated lowercase effort
+                } else {
+                    error!("Invalid reasoning_effort '{}' specified. Defaulting to 'high'", effort);
+                    "high".to_string() // Default to high if invalid
+                };
 
-            cmd_args.push("--reasoning-effort".to_string());
-            cmd_args.push(validated_effort.clone());
-            debug!("Using reasoning_effort: {}", validated_effort);
+                cmd_args.push("--reasoning-effort".to_string());
+                cmd_args.push(validated_effort.clone());
+                debug!("Using reasoning_effort: {}", validated_effort);
+            }
+            None => {
+                if !params.reasoning_effort.trim().is_empty() {
+                    let reason = format!(
+                        "reasoning-effort is only supported for OpenAI o-series models (provider is '{}')",
+                        provider
+                    );
+                    error!("Dropping reasoning_effort: {}", reason);
+                    dropped.push(DroppedOption {
+                        option: "reasoning_effort".to_string(),
+                        value: params.reasoning_effort.clone(),
+                        reason,
+                    });
+                }
+            }
         }
 
         // Add any additional options from the options string if it's not empty
@@ -174,18 +424,269 @@ impl AiderExecutor {
             }
         }
 
-        cmd_args
-    
+        // Pre-select relevant files for aider's editable/read set, so users
+        // don't have to hand-enumerate them alongside the prose message.
+        // Unsupported for remote runs: selection walks the local `directory`,
+        // which generally doesn't exist on the remote host.
+        if !params.context_query.trim().is_empty() {
+            if params.remote.is_some() {
+                debug!("Ignoring context_query: context file selection is not supported for remote runs");
+            } else {
+                let context_files = Self::select_context_files(&params.directory, &params.context_query);
+                if !context_files.is_empty() {
+                    info!(
+                        "Selected {} context file(s) for query '{}': {:?}",
+                        context_files.len(), params.context_query, context_files
+                    );
+                    cmd_args.extend(context_files);
+                } else {
+                    debug!("No matching context files found for query '{}'", params.context_query);
+                }
+            }
+        }
+
+        (cmd_args, dropped)
     }
-    
+
+    /// Returns the fully-resolved, shell-quoted command line that `execute`/
+    /// `execute_streaming` would run for `params`, without spawning aider.
+    /// Built from the same `build_command_args` output used for real
+    /// execution, so it can never drift from it, and followed by a `#`-prefixed
+    /// line per option dropped for the resolved provider, if any.
+    pub fn preview(&self, params: &AiderParams) -> String {
+        let (cmd_args, dropped) = self.build_command_args(params);
+        let provider = Self::resolve_provider(params);
+        let env_assignments = Self::env_assignments_for(&provider, params);
+
+        let command_line = if let Some(remote) = &params.remote {
+            let command = Self::build_remote_command(remote, &cmd_args, &env_assignments);
+            let std_command = command.as_std();
+            std::iter::once(std_command.get_program())
+                .chain(std_command.get_args())
+                .map(|arg| Self::shell_quote(&arg.to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            std::iter::once("aider".to_string())
+                .chain(cmd_args.iter().cloned())
+                .map(|arg| Self::shell_quote(&arg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        if dropped.is_empty() {
+            command_line
+        } else {
+            let notes = dropped
+                .iter()
+                .map(|d| format!("# dropped {} = '{}': {}", d.option, d.value, d.reason))
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("{}\n{}", command_line, notes)
+        }
+    }
+
+    /// Tokenizes `text` into lowercase alphanumeric (plus `_`) terms, for use
+    /// as a bag-of-words vector in `select_context_files`'s scoring.
+    fn tokenize(text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric() && c != '_')
+            .filter(|s| s.len() > 1)
+            .map(|s| s.to_lowercase())
+            .collect()
+    }
+
+    /// Builds a term -> frequency map from a token list, for cosine scoring.
+    fn term_frequencies(tokens: &[String]) -> HashMap<String, f64> {
+        let mut counts: HashMap<String, f64> = HashMap::new();
+        for token in tokens {
+            *counts.entry(token.clone()).or_insert(0.0) += 1.0;
+        }
+        counts
+    }
+
+    /// Cosine similarity between two term-frequency vectors; 0.0 if either is empty.
+    fn cosine_similarity(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+        let dot: f64 = a
+            .iter()
+            .filter_map(|(term, a_count)| b.get(term).map(|b_count| a_count * b_count))
+            .sum();
+        let norm_a = a.values().map(|v| v * v).sum::<f64>().sqrt();
+        let norm_b = b.values().map(|v| v * v).sum::<f64>().sqrt();
+        if norm_a == 0.0 || norm_b == 0.0 {
+            0.0
+        } else {
+            dot / (norm_a * norm_b)
+        }
+    }
+
+    /// Walks `directory` (respecting .gitignore), scores each source file
+    /// against `query` by cosine similarity over term-frequency vectors, and
+    /// returns the highest-scoring file paths, capped to
+    /// `MAX_CONTEXT_FILES` entries and `MAX_CONTEXT_BYTES` total size. Paths
+    /// are returned relative to `directory`, since aider itself is invoked
+    /// with `directory` as its `current_dir`.
+    fn select_context_files(directory: &str, query: &str) -> Vec<String> {
+        const MAX_CONTEXT_FILES: usize = 10;
+        const MAX_CONTEXT_BYTES: u64 = 2 * 1024 * 1024;
+
+        let query_terms = Self::term_frequencies(&Self::tokenize(query));
+        if query_terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scored: Vec<(f64, PathBuf, u64)> = Vec::new();
+
+        for entry in WalkBuilder::new(directory).build().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let metadata = match std::fs::metadata(path) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            // Skip unreadable/binary files rather than failing the whole selection.
+            let contents = match std::fs::read_to_string(path) {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+            let file_terms = Self::term_frequencies(&Self::tokenize(&contents));
+            let score = Self::cosine_similarity(&query_terms, &file_terms);
+            if score > 0.0 {
+                scored.push((score, path.to_path_buf(), metadata.len()));
+            }
+        }
+
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut selected = Vec::new();
+        let mut total_bytes: u64 = 0;
+        for (score, path, size) in scored {
+            if selected.len() >= MAX_CONTEXT_FILES {
+                break;
+            }
+            if total_bytes + size > MAX_CONTEXT_BYTES {
+                continue;
+            }
+            total_bytes += size;
+            let relative_path = path.strip_prefix(directory).unwrap_or(&path);
+            debug!("Context file candidate (score {:.3}): {}", score, relative_path.display());
+            selected.push(relative_path.to_string_lossy().to_string());
+        }
+
+        selected
+    }
+
+    /// Resolves the provider for `params`: the explicit value if it names a
+    /// known provider, an invalid explicit value coerced to `"anthropic"`,
+    /// or auto-detection if `params.provider` is empty. Mirrors the logic
+    /// `build_command_args` applies internally, for callers (result-struct
+    /// population, `preview`) that need the resolved provider without
+    /// duplicating `build_command_args`'s own copy of it.
+    fn resolve_provider(params: &AiderParams) -> String {
+        if !params.provider.trim().is_empty() {
+            let p_l = params.provider.trim().to_lowercase();
+            if KNOWN_PROVIDERS.contains(&p_l.as_str()) { p_l } else { "anthropic".to_string() }
+        } else {
+            Self::detect_provider()
+        }
+    }
+
+    /// Env vars that must be set on whichever process actually runs aider
+    /// (local or remote) for `provider`/`params`, e.g. `OLLAMA_API_BASE` since
+    /// ollama is never configured through a CLI flag.
+    fn env_assignments_for(provider: &str, params: &AiderParams) -> Vec<(String, String)> {
+        let mut env_assignments = Vec::new();
+        if provider == "ollama" {
+            if let Some(base) = Self::resolve_api_base(provider, &params.api_base) {
+                env_assignments.push(("OLLAMA_API_BASE".to_string(), base));
+            }
+        }
+        env_assignments
+    }
+
+    /// Looks up the declarative capabilities for `provider`. Unknown providers
+    /// fall back to the most conservative shape (cloud, no default model, no
+    /// reasoning-effort support) rather than panicking, since `provider` may
+    /// have already been coerced to `"anthropic"` by the caller for invalid input.
+    fn provider_capabilities(provider: &str) -> ProviderCapabilities {
+        match provider {
+            "anthropic" => ProviderCapabilities {
+                default_model: Some("anthropic/claude-3-7-sonnet-20250219"),
+                requires_api_key: true,
+                reasoning_effort_values: None,
+                api_base_mode: ApiBaseMode::Unsupported,
+            },
+            "openai" => ProviderCapabilities {
+                default_model: Some("openai/o3-mini"),
+                requires_api_key: true,
+                reasoning_effort_values: Some(&["low", "medium", "high"]),
+                api_base_mode: ApiBaseMode::Unsupported,
+            },
+            "gemini" => ProviderCapabilities {
+                default_model: Some("gemini/gemini-2.5-pro-preview-03-25"),
+                requires_api_key: true,
+                reasoning_effort_values: None,
+                api_base_mode: ApiBaseMode::Unsupported,
+            },
+            "ollama" => ProviderCapabilities {
+                default_model: Some("ollama/llama3"),
+                requires_api_key: false,
+                reasoning_effort_values: None,
+                api_base_mode: ApiBaseMode::EnvVar,
+            },
+            "openai-compatible" => ProviderCapabilities {
+                default_model: None,
+                requires_api_key: false,
+                reasoning_effort_values: None,
+                api_base_mode: ApiBaseMode::CliFlag,
+            },
+            _ => {
+                error!("No capability entry for provider '{}'; treating as cloud with no default model", provider);
+                ProviderCapabilities {
+                    default_model: None,
+                    requires_api_key: true,
+                    reasoning_effort_values: None,
+                    api_base_mode: ApiBaseMode::Unsupported,
+                }
+            }
+        }
+    }
+
+    /// Resolves the base URL for local/self-hosted providers: an explicit
+    /// `api_base` param wins, then provider-specific env vars, then a sane
+    /// default for Ollama. Without an explicit `api_base`, returns `None` for
+    /// cloud providers and for openai-compatible.
+    fn resolve_api_base(provider: &str, api_base_param: &str) -> Option<String> {
+        if !api_base_param.trim().is_empty() {
+            return Some(api_base_param.trim().to_string());
+        }
+
+        match provider {
+            "ollama" => std::env::var("OLLAMA_API_BASE")
+                .ok()
+                .or_else(|| std::env::var("OLLAMA_HOST").ok())
+                .or_else(|| Some("http://localhost:11434".to_string())),
+            "openai-compatible" => None,
+            _ => None,
+        }
+    }
+
     /// Detects the provider based on available API keys in the environment.
-    /// Prioritizes Gemini > Anthropic > OpenAI if multiple keys are present. Defaults to Gemini.
+    /// Checks for an Ollama endpoint first (OLLAMA_API_BASE/OLLAMA_HOST), since
+    /// their presence is an explicit signal to use a local model. Otherwise
+    /// prioritizes Gemini > Anthropic > OpenAI if multiple keys are present.
+    /// Defaults to Gemini.
     fn detect_provider() -> String {
+        let has_ollama = std::env::var("OLLAMA_API_BASE").is_ok() || std::env::var("OLLAMA_HOST").is_ok();
         let has_gemini = std::env::var("GEMINI_API_KEY").is_ok();
         let has_anthropic = std::env::var("ANTHROPIC_API_KEY").is_ok();
         let has_openai = std::env::var("OPENAI_API_KEY").is_ok();
 
-        if has_gemini {
+        if has_ollama {
+            debug!("Detected OLLAMA_API_BASE/OLLAMA_HOST, selecting 'ollama' provider.");
+            "ollama".to_string()
+        } else if has_gemini {
             debug!("Detected GEMINI_API_KEY, selecting 'gemini' provider.");
             "gemini".to_string()
         } else if has_anthropic {
@@ -200,14 +701,86 @@ impl AiderExecutor {
         }
     }
 
+    /// Quotes `s` for safe inclusion in the single command string sent to the
+    /// remote shell over `ssh`, so values like the aider message or a path
+    /// containing spaces survive the trip intact.
+    fn shell_quote(s: &str) -> String {
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@".contains(c)) {
+            s.to_string()
+        } else {
+            format!("'{}'", s.replace('\'', "'\\''"))
+        }
+    }
+
+    /// Builds the `ssh` invocation that runs `cmd_args` (the same args
+    /// `build_command_args` produces for a local run) against `remote`,
+    /// `cd`-ing into `remote.remote_directory` and exporting `env_assignments`
+    /// first. Returns an unspawned `Command` with both pipes configured, like
+    /// the local path, so the caller's streaming logic is unaware of the difference.
+    fn build_remote_command(remote: &RemoteTarget, cmd_args: &[String], env_assignments: &[(String, String)]) -> Command {
+        let mut parts: Vec<String> = env_assignments
+            .iter()
+            .map(|(key, value)| format!("export {}={}", key, Self::shell_quote(value)))
+            .collect();
+
+        let aider_invocation = std::iter::once("aider".to_string())
+            .chain(cmd_args.iter().cloned())
+            .map(|arg| Self::shell_quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        parts.push(format!("cd {} && {}", Self::shell_quote(&remote.remote_directory), aider_invocation));
+
+        let mut command = Command::new("ssh");
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        if remote.port != 0 {
+            command.arg("-p").arg(remote.port.to_string());
+        }
+        let host_arg = if remote.user.trim().is_empty() {
+            remote.host.clone()
+        } else {
+            format!("{}@{}", remote.user.trim(), remote.host)
+        };
+        command.arg(host_arg).arg(parts.join(" && "));
+        command
+    }
+
     pub async fn execute(&self, params: AiderParams) -> Result<AiderResult> {
-        // Validate directory exists
-        let dir_path = PathBuf::from(&params.directory);
-        if !dir_path.exists() {
-            return Err(anyhow!("Directory '{}' does not exist", params.directory));
+        let mut events = self.execute_streaming(params).await?;
+
+        let mut final_result = None;
+        while let Some(event) = events.recv().await {
+            if let AiderEvent::Done(result) = event {
+                final_result = Some(result);
+            }
         }
-        if !dir_path.is_dir() {
-            return Err(anyhow!("Path '{}' is not a directory", params.directory));
+
+        final_result.ok_or_else(|| anyhow!("aider exited without producing a result"))
+    }
+
+    /// Like `execute`, but spawns aider and streams its stdout/stderr back
+    /// line-by-line through the returned channel as they're produced, instead
+    /// of blocking until the whole run completes. A final `AiderEvent::Done`
+    /// carries the same `AiderResult` that `execute` returns.
+    pub async fn execute_streaming(&self, params: AiderParams) -> Result<mpsc::Receiver<AiderEvent>> {
+        // Validate the working tree. For a remote run, `remote_directory` lives
+        // on the other host and can't be checked locally; `directory` is then
+        // only used to resolve a relative `history_dir` (context_query
+        // selection is skipped for remote runs, see `build_command_args`).
+        if let Some(remote) = &params.remote {
+            if remote.host.trim().is_empty() {
+                return Err(anyhow!("remote.host cannot be empty"));
+            }
+            if remote.remote_directory.trim().is_empty() {
+                return Err(anyhow!("remote.remote_directory cannot be empty"));
+            }
+        } else {
+            let dir_path = PathBuf::from(&params.directory);
+            if !dir_path.exists() {
+                return Err(anyhow!("Directory '{}' does not exist", params.directory));
+            }
+            if !dir_path.is_dir() {
+                return Err(anyhow!("Path '{}' is not a directory", params.directory));
+            }
         }
 
         // Basic validation of the message
@@ -216,68 +789,363 @@ impl AiderExecutor {
         }
 
         // Build command arguments (this also determines the provider)
-        let cmd_args = self.build_command_args(&params);
-        
+        let (cmd_args, dropped_options) = self.build_command_args(&params);
+        for dropped in &dropped_options {
+            error!(
+                "Option '{}' (value: '{}') was dropped: {}",
+                dropped.option, dropped.value, dropped.reason
+            );
+        }
+
         // Extract provider and model used (determined during arg building)
         // This is a bit indirect, ideally build_command_args would return them too.
         // We re-determine provider here for the result struct.
-        let provider = if !params.provider.trim().is_empty() {
-             let p_l = params.provider.trim().to_lowercase();
-             if ["anthropic", "openai", "gemini"].contains(&p_l.as_str()) { p_l } else { Self::detect_provider() }
-        } else {
-            Self::detect_provider()
-        };
+        let provider = Self::resolve_provider(&params);
 
         // Re-determine model used for the result struct
         let model = if !params.model.trim().is_empty() {
             Some(params.model.trim().to_string())
         } else {
-            std::env::var("AIDER_MODEL").ok().or_else(|| {
-                match provider.as_str() {
-                    "anthropic" => Some("anthropic/claude-3-7-sonnet-20250219".to_string()),
-                    "openai" => Some("openai/o3-mini".to_string()),
-                    "gemini" => Some("gemini/gemini-2.5-pro-preview-03-25".to_string()), // Updated default model
-                    _ => None,
-                }
-            })
-        }; // <-- Add missing semicolon here
+            std::env::var("AIDER_MODEL")
+                .ok()
+                .or_else(|| Self::provider_capabilities(&provider).default_model.map(|m| m.to_string()))
+        };
 
         debug!("Running aider with args: {:?}", cmd_args);
-        info!("Executing aider in directory: {}", params.directory);
 
-        // Execute aider command
-        let output = Command::new("aider")
-            .args(&cmd_args)
-            .current_dir(&params.directory)
-            .output()
-            .await
-            .map_err(|e| anyhow!("Failed to execute aider: {}", e))?;
+        let env_assignments = Self::env_assignments_for(&provider, &params);
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        // `directory` in the result/history records is the target working tree,
+        // expressed as `host:remote_directory` for a remote run so it's still
+        // meaningful without the local `AiderParams.directory` value.
+        let (mut command, directory) = if let Some(remote) = &params.remote {
+            info!(
+                "Executing aider remotely on {} (remote_directory: {})",
+                remote.host, remote.remote_directory
+            );
+            let command = Self::build_remote_command(remote, &cmd_args, &env_assignments);
+            (command, format!("{}:{}", remote.host, remote.remote_directory))
+        } else {
+            info!("Executing aider in directory: {}", params.directory);
+            let mut command = Command::new("aider");
+            command
+                .args(&cmd_args)
+                .current_dir(&params.directory)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            for (key, value) in &env_assignments {
+                debug!("Setting {}={} for child process", key, value);
+                command.env(key, value);
+            }
+            (command, params.directory.clone())
+        };
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow!("Failed to spawn aider: {}", e))?;
+
+        let child_stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("Failed to capture aider's stdout"))?;
+        let child_stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow!("Failed to capture aider's stderr"))?;
 
-        // Log results
-        if !output.status.success() {
-            error!("Aider command failed with status: {:?}", output.status);
-            if !stderr.is_empty() {
-                error!("Stderr: {}", stderr);
+        let (tx, rx) = mpsc::channel(100);
+        let message = params.message.clone();
+        let record_history = params.record_history;
+        let base_directory = params.directory.clone();
+        let history_dir = params.history_dir.clone();
+        let full_args = cmd_args.clone();
+
+        tokio::spawn(async move {
+            let mut stdout_lines = BufReader::new(child_stdout).lines();
+            let mut stderr_lines = BufReader::new(child_stderr).lines();
+            let mut stdout_buf = String::new();
+            let mut stderr_buf = String::new();
+            let mut stdout_done = false;
+            let mut stderr_done = false;
+
+            while !stdout_done || !stderr_done {
+                tokio::select! {
+                    line = stdout_lines.next_line(), if !stdout_done => {
+                        match line {
+                            Ok(Some(line)) => {
+                                stdout_buf.push_str(&line);
+                                stdout_buf.push('\n');
+                                let _ = tx.send(AiderEvent::Stdout(line)).await;
+                            }
+                            Ok(None) => stdout_done = true,
+                            Err(e) => {
+                                error!("Error reading aider stdout: {}", e);
+                                stdout_done = true;
+                            }
+                        }
+                    }
+                    line = stderr_lines.next_line(), if !stderr_done => {
+                        match line {
+                            Ok(Some(line)) => {
+                                stderr_buf.push_str(&line);
+                                stderr_buf.push('\n');
+                                let _ = tx.send(AiderEvent::Stderr(line)).await;
+                            }
+                            Ok(None) => stderr_done = true,
+                            Err(e) => {
+                                error!("Error reading aider stderr: {}", e);
+                                stderr_done = true;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let status = match child.wait().await {
+                Ok(status) => status,
+                Err(e) => {
+                    error!("Failed to wait for aider process: {}", e);
+                    return;
+                }
+            };
+
+            if !status.success() {
+                error!("Aider command failed with status: {:?}", status);
+            } else {
+                info!("Aider command completed successfully");
             }
+
+            let result = AiderResult {
+                success: status.success(),
+                status: status.code().unwrap_or(-1),
+                stdout: stdout_buf,
+                stderr: stderr_buf,
+                directory,
+                message,
+                provider,
+                model,
+            };
+
+            if record_history {
+                let record = AiderRunRecord {
+                    timestamp: std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0),
+                    directory: result.directory.clone(),
+                    provider: result.provider.clone(),
+                    model: result.model.clone(),
+                    message: result.message.clone(),
+                    status: result.status,
+                    stdout_len: result.stdout.len(),
+                    stderr_len: result.stderr.len(),
+                    full_args,
+                };
+                record_run(&base_directory, &history_dir, &record).await;
+            }
+
+            let _ = tx.send(AiderEvent::Done(result)).await;
+        });
+
+        Ok(rx)
+    }
+
+    /// Runs `steps` as a sequence of aider invocations against the same
+    /// `directory`, stopping at the first failing step. This lets a caller
+    /// drive a dependent sequence (e.g. scaffold -> implement -> add tests ->
+    /// fix) in one call instead of manually checking success between steps.
+    pub async fn execute_plan(&self, params: AiderPlanParams) -> Result<AiderPlanResult> {
+        if params.steps.is_empty() {
+            return Err(anyhow!("Plan must contain at least one step"));
+        }
+
+        // Resolve provider/model once up front so every step in the plan
+        // runs against the same target, regardless of env changes mid-plan.
+        let provider = if !params.provider.trim().is_empty() {
+            let p_l = params.provider.trim().to_lowercase();
+            if KNOWN_PROVIDERS.contains(&p_l.as_str()) { p_l } else { Self::detect_provider() }
         } else {
-            info!("Aider command completed successfully");
-            debug!("Stdout length: {}", stdout.len());
-        }
-
-        Ok(AiderResult {
-            success: output.status.success(),
-            status: output.status.code().unwrap_or(-1),
-            stdout,
-            stderr,
-            directory: params.directory,
-            message: params.message,
-            provider, // Use the determined provider
-            model,    // Use the determined model
+            Self::detect_provider()
+        };
+        let model = if !params.model.trim().is_empty() {
+            params.model.trim().to_string()
+        } else {
+            std::env::var("AIDER_MODEL").ok().unwrap_or_else(|| {
+                Self::provider_capabilities(&provider)
+                    .default_model
+                    .map(|m| m.to_string())
+                    .unwrap_or_default()
+            })
+        };
+
+        let mut results = Vec::with_capacity(params.steps.len());
+        let mut aborted_at_step = None;
+
+        for (i, step_message) in params.steps.iter().enumerate() {
+            info!("Executing plan step {}/{} in {}", i + 1, params.steps.len(), params.directory);
+
+            let step_params = AiderParams {
+                directory: params.directory.clone(),
+                message: step_message.clone(),
+                options: params.options.clone(),
+                provider: provider.clone(),
+                model: model.clone(),
+                reasoning_effort: params.reasoning_effort.clone(),
+                api_base: params.api_base.clone(),
+                record_history: params.record_history,
+                history_dir: params.history_dir.clone(),
+                context_query: params.context_query.clone(),
+                remote: None,
+            };
+
+            let directory = step_params.directory.clone();
+            let message = step_params.message.clone();
+            let result = self.execute(step_params).await.unwrap_or_else(|e| {
+                error!("Plan step {} in '{}' errored before completing: {}", i + 1, params.directory, e);
+                AiderResult {
+                    success: false,
+                    status: -1,
+                    stdout: String::new(),
+                    stderr: e.to_string(),
+                    directory,
+                    message,
+                    provider: provider.clone(),
+                    model: Some(model.clone()),
+                }
+            });
+            let success = result.success;
+            results.push(result);
+
+            if !success {
+                error!("Plan step {} failed in '{}'; aborting remaining steps", i + 1, params.directory);
+                aborted_at_step = Some(i);
+                break;
+            }
+        }
+
+        Ok(AiderPlanResult {
+            results,
+            aborted_at_step,
         })
     }
+
+    /// Runs each entry in `params_list` as its own `execute` call, fanning out
+    /// across directories with at most `max_concurrency` runs in flight at
+    /// once (defaults to `num_cpus::get()`). One failing run never aborts the
+    /// others; its failure is captured in the corresponding `AiderResult`.
+    /// Results are returned in the same order as `params_list`.
+    pub async fn batch(
+        &self,
+        params_list: Vec<AiderParams>,
+        max_concurrency: Option<usize>,
+    ) -> Vec<AiderResult> {
+        let limit = max_concurrency.unwrap_or_else(num_cpus::get).max(1);
+        let semaphore = Arc::new(Semaphore::new(limit));
+
+        let tasks: Vec<_> = params_list
+            .into_iter()
+            .map(|params| {
+                let semaphore = Arc::clone(&semaphore);
+                let directory = params.directory.clone();
+                let message = params.message.clone();
+
+                tokio::spawn(async move {
+                    let _permit = semaphore
+                        .acquire_owned()
+                        .await
+                        .expect("batch semaphore was unexpectedly closed");
+
+                    AiderExecutor::new().execute(params).await.unwrap_or_else(|e| {
+                        error!("Batch run in '{}' failed: {}", directory, e);
+                        AiderResult {
+                            success: false,
+                            status: -1,
+                            stdout: String::new(),
+                            stderr: e.to_string(),
+                            directory,
+                            message,
+                            provider: "unknown".to_string(),
+                            model: None,
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        join_all(tasks)
+            .await
+            .into_iter()
+            .map(|joined| {
+                joined.unwrap_or_else(|join_err| {
+                    error!("Batch task panicked: {}", join_err);
+                    AiderResult {
+                        success: false,
+                        status: -1,
+                        stdout: String::new(),
+                        stderr: format!("Batch task panicked: {}", join_err),
+                        directory: "unknown".to_string(),
+                        message: "unknown".to_string(),
+                        provider: "unknown".to_string(),
+                        model: None,
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+/// Parameters for running an ordered sequence of aider steps against the
+/// same working directory via `AiderExecutor::execute_plan`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AiderPlanParams {
+    #[schemars(description = "The directory path where aider should run (must exist and contain code files)")]
+    pub directory: String,
+
+    #[schemars(description = "An ordered list of instructions. Each is run as its own aider invocation against the same directory; if a step fails, later steps are skipped.")]
+    pub steps: Vec<String>,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: A space-separated string of additional command-line options to pass to aider for every step. Leave empty for none.")]
+    pub options: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: The provider to use for every step (e.g., 'anthropic', 'openai', 'gemini', 'ollama', 'openai-compatible'). Leave empty to auto-detect once, up front.")]
+    pub provider: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: The model to use for every step. Leave empty to use AIDER_MODEL env var or provider default, resolved once up front.")]
+    pub model: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: Reasoning effort level for OpenAI models, applied to every step. Values: 'low', 'medium', 'high'.")]
+    pub reasoning_effort: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: Base URL for a local or self-hosted endpoint, applied to every step.")]
+    pub api_base: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: If true, append a JSON-lines record of every step's run to the history store. Defaults to false.")]
+    pub record_history: bool,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: Directory the run history is stored under. Defaults to '.aider-mcp/history'. Only used when `record_history` is true.")]
+    pub history_dir: String,
+
+    #[serde(default)]
+    #[schemars(description = "Optional: When non-empty, automatically selects the most relevant files under `directory` for this query and adds them to aider's file list for every step.")]
+    pub context_query: String,
+}
+
+/// The outcome of running an `AiderPlanParams` sequence: one `AiderResult`
+/// per step actually executed, plus the index of the step that stopped the
+/// plan early, if any.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AiderPlanResult {
+    /// Results for each step that was executed, in order.
+    pub results: Vec<AiderResult>,
+    /// The index (0-based) of the step that failed and stopped the plan, if any.
+    pub aborted_at_step: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -320,8 +1188,53 @@ impl AiderTool {
                 )
             },
             Err(e) => {
-                error!("Aider execution failed: {}", e);
-                format!("Error executing aider: {}", e)
+                error!("Aider execution failed: {}", e);
+                format!("Error executing aider: {}", e)
+            }
+        }
+    }
+
+    #[tool(description = "AI pair programming tool for running a multi-step plan of aider invocations against the same directory. Each step in 'steps' runs sequentially; if one fails, later steps are skipped. Use for dependent sequences like scaffold -> implement -> add tests -> fix. Like 'aider', it has NO CONTEXT from the conversation; all necessary details must be in each step's instructions.")]
+    pub async fn aider_plan(
+        &self,
+        #[tool(aggr)] params: AiderPlanParams
+    ) -> String {
+        info!("Running aider plan with {} step(s) in directory: {}", params.steps.len(), params.directory);
+
+        let executor = AiderExecutor::new();
+
+        match executor.execute_plan(params).await {
+            Ok(plan_result) => {
+                let mut summary = format!(
+                    "Aider plan: {} step(s) executed{}\n",
+                    plan_result.results.len(),
+                    match plan_result.aborted_at_step {
+                        Some(i) => format!(", aborted at step {} (1-indexed)", i + 1),
+                        None => " (completed)".to_string(),
+                    }
+                );
+
+                for (i, result) in plan_result.results.iter().enumerate() {
+                    let model_info = match &result.model {
+                        Some(model) => format!("Provider: {} | Model: {}", result.provider, model),
+                        None => format!("Provider: {}", result.provider),
+                    };
+                    summary.push_str(&format!(
+                        "\n--- Step {} [{}] {} ---\nExit status: {}\n\nSTDOUT:\n{}\n\nSTDERR:\n{}\n",
+                        i + 1,
+                        model_info,
+                        if result.success { "succeeded" } else { "failed" },
+                        result.status,
+                        result.stdout,
+                        result.stderr
+                    ));
+                }
+
+                summary
+            },
+            Err(e) => {
+                error!("Aider plan execution failed: {}", e);
+                format!("Error executing aider plan: {}", e)
             }
         }
     }
@@ -361,10 +1274,15 @@ mod tests {
                 provider: "anthropic".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
             // We don't actually execute the command, just check the validation logic
             // by inspecting the command that would be built
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--api-key".to_string()));
             
             // Test with valid provider: openai
@@ -375,8 +1293,13 @@ mod tests {
                 provider: "openai".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--api-key".to_string()));
             
             // Test with invalid provider - should default to anthropic
@@ -387,16 +1310,142 @@ mod tests {
                 provider: "invalid_provider".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             // The provider should be defaulted to anthropic
             assert!(cmd_args.iter().any(|arg| arg.contains("anthropic=")));
-            
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // Test the 'ollama' and 'openai-compatible' local/self-hosted providers
+    #[test]
+    fn test_local_provider_validation() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = create_temp_dir().await.unwrap();
+            let executor = AiderExecutor::new();
+
+            // Ollama: no API key required; OLLAMA_API_BASE/HOST unset, so the
+            // default endpoint is used via env rather than a CLI flag.
+            env::remove_var("OLLAMA_API_BASE");
+            env::remove_var("OLLAMA_HOST");
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "Test message".to_string(),
+                options: "".to_string(),
+                provider: "ollama".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
+            };
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
+            assert!(!cmd_args.contains(&"--api-key".to_string()));
+            let model_index = cmd_args.iter().position(|arg| arg == "--model").unwrap();
+            assert_eq!(cmd_args[model_index + 1], "ollama/llama3");
+
+            // openai-compatible: explicit api_base becomes --openai-api-base
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "Test message".to_string(),
+                options: "".to_string(),
+                provider: "openai-compatible".to_string(),
+                model: "my-local-model".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "http://localhost:8000/v1".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
+            };
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
+            assert!(!cmd_args.contains(&"--api-key".to_string()));
+            assert!(cmd_args.contains(&"--openai-api-base".to_string()));
+            let base_index = cmd_args.iter().position(|arg| arg == "--openai-api-base").unwrap();
+            assert_eq!(cmd_args[base_index + 1], "http://localhost:8000/v1");
+
             // Handle cleanup gracefully
             let _ = fs::remove_dir_all(temp_dir).await;
         });
     }
 
+    // Test that context_query selects and appends the most relevant files
+    #[test]
+    fn test_context_query_selects_relevant_files() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = format!("/tmp/aider_context_test_{}", std::process::id());
+            let _ = fs::remove_dir_all(&temp_dir).await;
+            fs::create_dir_all(&temp_dir).await.unwrap();
+
+            fs::write(
+                format!("{}/widget_parser.rs", temp_dir),
+                "fn parse_widget_config(raw: &str) -> Widget { todo!() }",
+            )
+            .await
+            .unwrap();
+            fs::write(
+                format!("{}/unrelated.rs", temp_dir),
+                "fn add(a: i32, b: i32) -> i32 { a + b }",
+            )
+            .await
+            .unwrap();
+
+            let executor = AiderExecutor::new();
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "Test message".to_string(),
+                options: "".to_string(),
+                provider: "anthropic".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "widget parse config".to_string(),
+                remote: None,
+            };
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
+            assert!(cmd_args.iter().any(|arg| arg.ends_with("widget_parser.rs")));
+            assert!(!cmd_args.iter().any(|arg| arg.ends_with("unrelated.rs")));
+            // Selected paths are relative to `directory` (aider's current_dir),
+            // not prefixed with it, so they resolve correctly.
+            assert!(!cmd_args.iter().any(|arg| arg.contains(&temp_dir)));
+
+            // An empty context_query selects nothing
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "Test message".to_string(),
+                options: "".to_string(),
+                provider: "anthropic".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
+            };
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
+            assert!(!cmd_args.iter().any(|arg| arg.ends_with(".rs")));
+
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
     // Test provider detection logic
     #[test]
     fn test_provider_detection() {
@@ -448,11 +1497,24 @@ mod tests {
         env::remove_var("OPENAI_API_KEY");
         assert_eq!(AiderExecutor::detect_provider(), "gemini");
 
+        // Case 8: OLLAMA_API_BASE set takes priority over cloud keys
+        env::set_var("OLLAMA_API_BASE", "http://localhost:11434");
+        env::set_var("GEMINI_API_KEY", "test_key");
+        assert_eq!(AiderExecutor::detect_provider(), "ollama");
+        env::remove_var("OLLAMA_API_BASE");
+
+        // Case 9: OLLAMA_HOST alone is also a valid signal
+        env::set_var("OLLAMA_HOST", "http://localhost:11434");
+        assert_eq!(AiderExecutor::detect_provider(), "ollama");
+        env::remove_var("OLLAMA_HOST");
+
         // Clean up env vars
         env::remove_var("GEMINI_API_KEY");
         env::remove_var("ANTHROPIC_API_KEY");
         env::remove_var("OPENAI_API_KEY");
         env::remove_var("GEMINI_API_KEY");
+        env::remove_var("OLLAMA_API_BASE");
+        env::remove_var("OLLAMA_HOST");
     }
     
     // Test default model selection logic
@@ -472,8 +1534,13 @@ mod tests {
                 provider: "anthropic".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--model".to_string()));
             let model_index = cmd_args.iter().position(|arg| arg == "--model").unwrap();
             assert_eq!(cmd_args[model_index + 1], "anthropic/claude-3-7-sonnet-20250219");
@@ -486,8 +1553,13 @@ mod tests {
                 provider: "openai".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--model".to_string()));
             let model_index = cmd_args.iter().position(|arg| arg == "--model").unwrap();
             assert_eq!(cmd_args[model_index + 1], "openai/o3-mini");
@@ -500,12 +1572,36 @@ mod tests {
                 provider: "gemini".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--model".to_string()));
             let model_index = cmd_args.iter().position(|arg| arg == "--model").unwrap();
             assert_eq!(cmd_args[model_index + 1], "gemini/gemini-2.5-pro-preview-03-25"); // Updated default model
-            
+
+            // Test default model for ollama
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "Test message".to_string(),
+                options: "".to_string(),
+                provider: "ollama".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
+            };
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
+            assert!(cmd_args.contains(&"--model".to_string()));
+            let model_index = cmd_args.iter().position(|arg| arg == "--model").unwrap();
+            assert_eq!(cmd_args[model_index + 1], "ollama/llama3");
+
             // Test custom model overrides default
             let params = AiderParams {
                 directory: temp_dir.clone(),
@@ -514,8 +1610,13 @@ mod tests {
                 provider: "anthropic".to_string(),
                 model: "claude-3-opus-20240229".to_string(),
                 reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--model".to_string()));
             let model_index = cmd_args.iter().position(|arg| arg == "--model").unwrap();
             assert_eq!(cmd_args[model_index + 1], "claude-3-opus-20240229");
@@ -542,8 +1643,13 @@ mod tests {
                 provider: "openai".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "high".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--reasoning-effort".to_string()));
             let effort_index = cmd_args.iter().position(|arg| arg == "--reasoning-effort").unwrap();
             assert_eq!(cmd_args[effort_index + 1], "high");
@@ -556,8 +1662,13 @@ mod tests {
                 provider: "openai".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "invalid_effort".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
             assert!(cmd_args.contains(&"--reasoning-effort".to_string()));
             let effort_index = cmd_args.iter().position(|arg| arg == "--reasoning-effort").unwrap();
             assert_eq!(cmd_args[effort_index + 1], "high");
@@ -570,11 +1681,17 @@ mod tests {
                 provider: "anthropic".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "high".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, dropped) = executor.build_command_args(&params);
             assert!(!cmd_args.contains(&"--reasoning-effort".to_string()));
+            assert!(dropped.iter().any(|d| d.option == "reasoning_effort" && d.reason.contains("OpenAI o-series")));
 
-            // Test reasoning_effort with Gemini - should be ignored
+            // Test reasoning_effort with Gemini - should be ignored, and recorded as dropped
             let params = AiderParams {
                 directory: temp_dir.clone(),
                 message: "Test message".to_string(),
@@ -582,10 +1699,114 @@ mod tests {
                 provider: "gemini".to_string(),
                 model: "".to_string(),
                 reasoning_effort: "high".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
             };
-            let cmd_args = executor.build_command_args(&params);
+            let (cmd_args, dropped) = executor.build_command_args(&params);
             assert!(!cmd_args.contains(&"--reasoning-effort".to_string()));
-            
+            assert!(dropped.iter().any(|d| d.option == "reasoning_effort" && d.value == "high"));
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // Test that api_base set for a cloud provider is dropped with a reason
+    #[test]
+    fn test_dropped_api_base_for_cloud_provider() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = create_temp_dir().await.unwrap();
+            let executor = AiderExecutor::new();
+
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "Test message".to_string(),
+                options: "".to_string(),
+                provider: "anthropic".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "http://localhost:8000/v1".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
+            };
+            let (cmd_args, dropped) = executor.build_command_args(&params);
+            assert!(!cmd_args.contains(&"--openai-api-base".to_string()));
+            assert!(dropped.iter().any(|d| d.option == "api_base" && d.value == "http://localhost:8000/v1"));
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // A single parametrized test covering every entry in the provider
+    // capability table, rather than one bespoke test per provider. Adding a
+    // provider to `provider_capabilities` should mean adding a case here,
+    // not writing a new test function.
+    #[test]
+    fn test_provider_capabilities_table() {
+        let rt = Runtime::new().unwrap();
+
+        // (provider, expected default model, reasoning_effort supported)
+        let cases = [
+            ("anthropic", Some("anthropic/claude-3-7-sonnet-20250219"), false),
+            ("openai", Some("openai/o3-mini"), true),
+            ("gemini", Some("gemini/gemini-2.5-pro-preview-03-25"), false),
+            ("ollama", Some("ollama/llama3"), false),
+            ("openai-compatible", None, false),
+        ];
+
+        rt.block_on(async {
+            let temp_dir = create_temp_dir().await.unwrap();
+            let executor = AiderExecutor::new();
+
+            for (provider, expected_model, supports_reasoning_effort) in cases {
+                let params = AiderParams {
+                    directory: temp_dir.clone(),
+                    message: "Test message".to_string(),
+                    options: "".to_string(),
+                    provider: provider.to_string(),
+                    model: "".to_string(),
+                    reasoning_effort: "medium".to_string(),
+                    api_base: "".to_string(),
+                    record_history: false,
+                    history_dir: "".to_string(),
+                    context_query: "".to_string(),
+                    remote: None,
+                };
+                let (cmd_args, dropped) = executor.build_command_args(&params);
+
+                match expected_model {
+                    Some(model) => {
+                        let model_index = cmd_args.iter().position(|arg| arg == "--model").unwrap();
+                        assert_eq!(cmd_args[model_index + 1], model, "unexpected default model for '{}'", provider);
+                    }
+                    None => {
+                        assert!(!cmd_args.contains(&"--model".to_string()), "expected no default model for '{}'", provider);
+                    }
+                }
+
+                let effort_dropped = dropped.iter().any(|d| d.option == "reasoning_effort");
+                assert_eq!(
+                    cmd_args.contains(&"--reasoning-effort".to_string()),
+                    supports_reasoning_effort,
+                    "unexpected reasoning_effort handling for '{}'",
+                    provider
+                );
+                assert_eq!(
+                    !effort_dropped,
+                    supports_reasoning_effort,
+                    "unexpected dropped-option bookkeeping for '{}'",
+                    provider
+                );
+            }
+
             // Handle cleanup gracefully
             let _ = fs::remove_dir_all(temp_dir).await;
         });
@@ -595,14 +1816,294 @@ mod tests {
     #[test]
     fn test_thinking_tokens_validation() {
         let rt = Runtime::new().unwrap();
-        
+
         rt.block_on(async {
             let temp_dir = create_temp_dir().await.unwrap();
             let executor = AiderExecutor::new();
-            
+
             // Test thinking_tokens validation is removed
             // Handle cleanup gracefully
             let _ = fs::remove_dir_all(temp_dir).await;
         });
     }
+
+    // Test that a remote target produces an `ssh` invocation wrapping the same
+    // aider args the local path would use, with the directory change and any
+    // env assignments folded into a single quoted remote command string.
+    #[test]
+    fn test_build_remote_command_wraps_ssh() {
+        let remote = RemoteTarget {
+            host: "build-box".to_string(),
+            user: "ci".to_string(),
+            port: 2222,
+            remote_directory: "/srv/my repo".to_string(),
+        };
+        let cmd_args = vec!["--message".to_string(), "fix the bug".to_string()];
+        let env_assignments = vec![("OLLAMA_API_BASE".to_string(), "http://localhost:11434".to_string())];
+
+        let command = AiderExecutor::build_remote_command(&remote, &cmd_args, &env_assignments);
+        let std_command = command.as_std();
+        assert_eq!(std_command.get_program(), "ssh");
+
+        let args: Vec<String> = std_command
+            .get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(args[0], "-p");
+        assert_eq!(args[1], "2222");
+        assert_eq!(args[2], "ci@build-box");
+
+        let remote_command = &args[3];
+        assert!(remote_command.contains("export OLLAMA_API_BASE=http://localhost:11434"));
+        assert!(remote_command.contains("cd '/srv/my repo'"));
+        assert!(remote_command.contains("aider --message 'fix the bug'"));
+    }
+
+    // Test the quoting helper directly: safe characters pass through unquoted,
+    // anything else is single-quoted with embedded quotes escaped.
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(AiderExecutor::shell_quote("simple-value_1.2:3@host"), "simple-value_1.2:3@host");
+        assert_eq!(AiderExecutor::shell_quote("has space"), "'has space'");
+        assert_eq!(AiderExecutor::shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(AiderExecutor::shell_quote(""), "''");
+    }
+
+    // Test that preview() reflects the same model/provider resolution and
+    // dropped-option diagnostics that build_command_args would produce, without
+    // actually running aider.
+    #[test]
+    fn test_preview_reflects_build_command_args() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = create_temp_dir().await.unwrap();
+            let executor = AiderExecutor::new();
+
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "fix the bug".to_string(),
+                options: "".to_string(),
+                provider: "gemini".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "high".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
+            };
+
+            let preview = executor.preview(&params);
+            assert!(preview.starts_with("aider "));
+            assert!(preview.contains("--message 'fix the bug'"));
+            assert!(preview.contains("--model gemini/gemini-2.5-pro-preview-03-25"));
+            assert!(preview.contains("# dropped reasoning_effort = 'high':"));
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // Test that preview() wraps the command in ssh for a remote target,
+    // matching the invocation execute_streaming would actually spawn.
+    #[test]
+    fn test_preview_wraps_remote_in_ssh() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = create_temp_dir().await.unwrap();
+            let executor = AiderExecutor::new();
+
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "fix the bug".to_string(),
+                options: "".to_string(),
+                provider: "anthropic".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: Some(RemoteTarget {
+                    host: "build-box".to_string(),
+                    user: "".to_string(),
+                    port: 0,
+                    remote_directory: "/srv/repo".to_string(),
+                }),
+            };
+
+            let preview = executor.preview(&params);
+            assert!(preview.starts_with("ssh build-box "));
+            assert!(preview.contains("cd /srv/repo"));
+            assert!(preview.contains("aider --message"));
+            assert!(preview.contains("fix the bug"));
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // Test that preview() includes the OLLAMA_API_BASE export for a remote
+    // ollama run, matching what execute_streaming actually sets on the remote
+    // process, so the preview never drifts from real execution.
+    #[test]
+    fn test_preview_includes_ollama_env_export_for_remote() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = create_temp_dir().await.unwrap();
+            let executor = AiderExecutor::new();
+
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "fix the bug".to_string(),
+                options: "".to_string(),
+                provider: "ollama".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "http://localhost:11434".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: Some(RemoteTarget {
+                    host: "build-box".to_string(),
+                    user: "".to_string(),
+                    port: 0,
+                    remote_directory: "/srv/repo".to_string(),
+                }),
+            };
+
+            let preview = executor.preview(&params);
+            assert!(preview.contains("export OLLAMA_API_BASE=http://localhost:11434"));
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // Test that an invalid provider string resolves the same way preview()
+    // reports it as build_command_args() actually builds for: coerced to
+    // "anthropic", not auto-detected, even with OLLAMA_API_BASE set in the
+    // environment (which would otherwise steer auto-detection to "ollama").
+    #[test]
+    fn test_preview_coerces_invalid_provider_like_build_command_args() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = create_temp_dir().await.unwrap();
+            let executor = AiderExecutor::new();
+
+            env::set_var("OLLAMA_API_BASE", "http://localhost:11434");
+
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "fix the bug".to_string(),
+                options: "".to_string(),
+                provider: "not-a-real-provider".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+                remote: None,
+            };
+
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
+            let preview = executor.preview(&params);
+
+            // build_command_args coerces the invalid provider to anthropic
+            // and never touches OLLAMA_API_BASE.
+            assert!(!cmd_args.iter().any(|arg| arg == "ollama"));
+            // preview must agree: no OLLAMA_API_BASE export, since the
+            // command that would actually run is anthropic, not ollama.
+            assert!(!preview.contains("OLLAMA_API_BASE"));
+
+            env::remove_var("OLLAMA_API_BASE");
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // Test that context_query is ignored (not appended as positional file
+    // args) when a remote target is set, since selection walks the local
+    // `directory`, which generally doesn't exist on the remote host.
+    #[test]
+    fn test_context_query_ignored_for_remote() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let temp_dir = format!("/tmp/aider_remote_context_test_{}", std::process::id());
+            let _ = fs::remove_dir_all(&temp_dir).await;
+            fs::create_dir_all(&temp_dir).await.unwrap();
+            fs::write(
+                format!("{}/widget_parser.rs", temp_dir),
+                "fn parse_widget_config(raw: &str) -> Widget { todo!() }",
+            )
+            .await
+            .unwrap();
+
+            let executor = AiderExecutor::new();
+            let params = AiderParams {
+                directory: temp_dir.clone(),
+                message: "Test message".to_string(),
+                options: "".to_string(),
+                provider: "anthropic".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "widget parse config".to_string(),
+                remote: Some(RemoteTarget {
+                    host: "build-box".to_string(),
+                    user: "".to_string(),
+                    port: 0,
+                    remote_directory: "/srv/repo".to_string(),
+                }),
+            };
+            let (cmd_args, _dropped) = executor.build_command_args(&params);
+            assert!(!cmd_args.iter().any(|arg| arg.ends_with("widget_parser.rs")));
+
+            // Handle cleanup gracefully
+            let _ = fs::remove_dir_all(temp_dir).await;
+        });
+    }
+
+    // Test that a step whose underlying execute() call returns a hard Err
+    // (not just an aider-exited-nonzero AiderResult) is caught into a failed
+    // AiderResult and aborts the plan with aborted_at_step set, instead of
+    // propagating the Err and discarding the steps already run.
+    #[test]
+    fn test_execute_plan_aborts_with_partial_results_on_hard_error() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let executor = AiderExecutor::new();
+            let missing_dir = format!("/tmp/aider_plan_missing_dir_{}", std::process::id());
+            let _ = fs::remove_dir_all(&missing_dir).await;
+
+            let params = AiderPlanParams {
+                directory: missing_dir.clone(),
+                steps: vec!["first step".to_string(), "second step".to_string()],
+                options: "".to_string(),
+                provider: "anthropic".to_string(),
+                model: "".to_string(),
+                reasoning_effort: "".to_string(),
+                api_base: "".to_string(),
+                record_history: false,
+                history_dir: "".to_string(),
+                context_query: "".to_string(),
+            };
+
+            let plan_result = executor.execute_plan(params).await.unwrap();
+            assert_eq!(plan_result.aborted_at_step, Some(0));
+            assert_eq!(plan_result.results.len(), 1);
+            assert!(!plan_result.results[0].success);
+            assert!(plan_result.results[0].stderr.contains("does not exist"));
+        });
+    }
 }